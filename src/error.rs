@@ -54,3 +54,15 @@ impl From<String> for Error {
     }
 }
 
+impl Error {
+    /// Converts into the error type expected by a streaming `hyper::Body`,
+    /// preserving the original `hyper::Error` where we have one.
+    pub fn into_hyper_error(self) -> ::hyper::Error {
+        match self {
+            Error::Hyper(e) => e,
+            Error::Io(e) => ::hyper::Error::Io(e),
+            e => ::hyper::Error::Io(::std::io::Error::new(::std::io::ErrorKind::Other, e.to_string())),
+        }
+    }
+}
+
@@ -3,23 +3,34 @@ extern crate futures;
 extern crate futures_cpupool;
 extern crate hyper;
 extern crate mime;
+extern crate mime_guess;
 
 #[macro_use]
 extern crate log;
 extern crate pretty_env_logger;
 extern crate time;
 
-use futures::{Async, Future, Poll};
+use flate2::Compression;
+use flate2::bufread::GzEncoder;
+use futures::{Async, Future, Poll, Stream};
 use futures_cpupool::Builder as PoolBuilder;
 use futures_cpupool::{CpuFuture, CpuPool};
-use hyper::{Method, Request, Response, StatusCode};
+use hyper::{Chunk, Method, Request, Response, StatusCode};
 use hyper::server::Http;
 use hyper::server::Service;
-use hyper::header::{AcceptEncoding, ContentEncoding, ContentLength, ContentType, Encoding};
-
+use hyper::header::{AcceptEncoding, AcceptRanges, ContentEncoding, ContentLength, ContentRange,
+                     ContentRangeSpec, ContentType, Encoding, EntityTag, ETag, HttpDate,
+                     IfModifiedSince, IfNoneMatch, LastModified, Location, QualityItem, Range,
+                     RangeUnit};
+use mime::Mime;
+
+use std::cmp;
+use std::cmp::Ordering;
+use std::fs;
 use std::fs::File;
 use std::path::{Path, PathBuf};
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use error::Error;
 use std::env;
@@ -30,18 +41,49 @@ mod error;
 struct StaticServer {
     root: PathBuf,
     pool: CpuPool,
+    autoindex: bool,
 }
 
 impl StaticServer {
-    fn spawn_read(&self, path: &Path, gzip: bool) -> ResponseFuture {
+    fn spawn_read(
+        &self,
+        path: &Path,
+        raw_path: &str,
+        accept_encoding: Option<AcceptEncoding>,
+        range: Option<Range>,
+        conditional: Conditional,
+        is_head: bool,
+    ) -> ResponseFuture {
         let mut canonical = self.canonicalize(path);
+        let mut autoindex_dir = None;
         if canonical.is_dir() {
-            canonical.push("index.html");
+            if !raw_path.ends_with('/') {
+                // Every relative href in an index.html or autoindex listing
+                // resolves against this directory's URL, so without a
+                // trailing slash the browser would resolve them one level
+                // up. Redirect to the slash-terminated form first.
+                return ResponseFuture::Redirect(format!("{}/", raw_path));
+            }
+            let index = canonical.join("index.html");
+            if index.is_file() {
+                canonical = index;
+            } else if self.autoindex {
+                autoindex_dir = Some(canonical.clone());
+            } else {
+                // No index and no listing allowed: fall through to the
+                // usual not-found handling for a nonexistent file.
+                canonical.push("index.html");
+            }
         }
-        if canonical.extension().is_none() {
+        if autoindex_dir.is_none() && canonical.extension().is_none() {
             canonical.set_extension("html");
         }
-        ResponseFuture::Found(self.pool.spawn_fn(move || read_file(&canonical, gzip)))
+        let pool = self.pool.clone();
+        let root = self.root.clone();
+        ResponseFuture::Found(self.pool.spawn_fn(move || match autoindex_dir {
+            Some(dir) => render_autoindex(&dir, &root, is_head),
+            None => read_file(&canonical, &root, accept_encoding, pool, range, conditional, is_head),
+        }))
     }
 
     fn canonicalize(&self, path: &Path) -> PathBuf {
@@ -58,59 +100,510 @@ impl StaticServer {
     }
 }
 
+/// Decodes `%XX` percent-escapes in a request path so that, e.g., `my%20file.txt`
+/// resolves to `my file.txt` on disk instead of being looked up literally.
+fn percent_decode(path: &str) -> Result<String, Error> {
+    // Work in bytes throughout and only attempt to interpret the result as
+    // UTF-8 once it's fully assembled. Slicing the source `&str` by the raw
+    // byte offsets of a `%XX` escape can land mid-codepoint when a stray `%`
+    // is immediately followed by a multi-byte UTF-8 character, which panics
+    // instead of erroring.
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if i + 3 > bytes.len() {
+                return Err(Error::from("truncated percent-encoding in path"));
+            }
+            match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                (Some(hi), Some(lo)) => {
+                    out.push(hi << 4 | lo);
+                    i += 3;
+                    continue;
+                }
+                _ => return Err(Error::from("invalid percent-encoding in path")),
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(out).map_err(|_| Error::from("path is not valid utf-8 after decoding"))
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
 const MIN_GZIP_SIZE: u64 = 1024;
+const CHUNK_SIZE: u64 = 64 * 1024;
 
-fn read_file(canonical: &Path, accept_gzip: bool) -> Result<Response, Error> {
+fn read_file(
+    canonical: &Path,
+    root: &Path,
+    accept_encoding: Option<AcceptEncoding>,
+    pool: CpuPool,
+    range: Option<Range>,
+    conditional: Conditional,
+    is_head: bool,
+) -> Result<Response, Error> {
     debug!("==> {:?}", canonical);
     let file = File::open(canonical)?;
-    let len = file.metadata()?.len();
 
-    let mut file = BufReader::new(file);
-    let mut body = Vec::with_capacity(len as usize);
+    // `canonicalize`'s component-popping guards against a literal `..` in
+    // the path, but it can't see through symlinks. Resolve the real path on
+    // disk and make sure it still lives under `root` before serving it.
+    let real_root = root.canonicalize()?;
+    let real_path = canonical.canonicalize()?;
+    if !real_path.starts_with(&real_root) {
+        return Ok(Response::new().with_status(StatusCode::Forbidden));
+    }
 
-    let gzip = accept_gzip && len > MIN_GZIP_SIZE;
+    let metadata = file.metadata()?;
+    let len = metadata.len();
+    let modified = metadata.modified()?;
+    let mtime_secs = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let last_modified = HttpDate::from(modified);
+    let etag = EntityTag::weak(format!("{}-{}", len, mtime_secs));
+
+    if is_not_modified(&conditional, &etag, &last_modified) {
+        return Ok(Response::new()
+            .with_status(StatusCode::NotModified)
+            .with_header(ETag(etag))
+            .with_header(LastModified(last_modified)));
+    }
 
-    if gzip {
-        use flate2::Compression;
-        use flate2::bufread::GzEncoder;
+    let byte_range = match satisfiable_range(range, len) {
+        Ok(r) => r,
+        Err(()) => {
+            return Ok(Response::new()
+                .with_status(StatusCode::RangeNotSatisfiable)
+                .with_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(len),
+                })));
+        }
+    };
 
-        let mut gz = GzEncoder::new(file, Compression::Fast);
-        gz.read_to_end(&mut body)?;
+    // Serving a range means the bytes on the wire must line up with the
+    // bytes of the file on disk, so neither a precompressed sidecar nor
+    // on-the-fly gzip is an option here.
+    let sidecar = if byte_range.is_none() {
+        best_sidecar(canonical, root, &accept_encoding, modified)?
     } else {
-        file.read_to_end(&mut body)?;
-    }
+        None
+    };
+    let accept_gzip = accept_encoding
+        .as_ref()
+        .map(|es| es.iter().any(|q| q.item == Encoding::Gzip))
+        .unwrap_or(false);
+    // On-the-fly gzip is CPU-bound work whose output length isn't known
+    // until it's done, so it isn't worth doing just to answer a HEAD with
+    // a body-less response; HEAD falls back to the plain, length-known path
+    // below instead, and its Content-Length/Content-Encoding won't always
+    // match what a subsequent GET would choose to send.
+    let gzip = sidecar.is_none() && accept_gzip && byte_range.is_none() && !is_head && len > MIN_GZIP_SIZE;
 
     let mut resp = Response::new()
-        .with_body(body)
-        .with_header(ContentLength(len));
+        .with_header(AcceptRanges(vec![RangeUnit::Bytes]))
+        .with_header(ETag(etag))
+        .with_header(LastModified(last_modified));
     if let Some(c) = content_type(canonical) {
         resp = resp.with_header(c);
     }
-    if gzip {
-        resp = resp.with_header(ContentEncoding(vec![Encoding::Gzip]));
+    if byte_range.is_none() {
+        // A range request always serves the plain file body, but otherwise
+        // the chosen representation (sidecar, on-the-fly gzip, or identity)
+        // depends on Accept-Encoding, so caches must key on it too.
+        resp.headers_mut().set_raw("Vary", "Accept-Encoding");
+    }
+
+    if let Some((sidecar_file, sidecar_len, encoding)) = sidecar {
+        resp = resp
+            .with_header(ContentLength(sidecar_len))
+            .with_header(ContentEncoding(vec![encoding]));
+        if !is_head {
+            let stream = ChunkedReadFile::new(sidecar_file, 0, sidecar_len, pool).map_err(Error::into_hyper_error);
+            resp = resp.with_body(Box::new(stream) as Box<Stream<Item = Chunk, Error = ::hyper::Error> + Send>);
+        }
+    } else if gzip {
+        // Compress on the pool as the body is streamed out, rather than
+        // buffering the whole (potentially multi-GB) file and its gzipped
+        // copy in memory before the first byte goes out. Since the
+        // compressed length isn't known up front, this response has no
+        // `Content-Length` and hyper falls back to chunked transfer-encoding.
+        let stream = GzipReadFile::new(file, pool).map_err(Error::into_hyper_error);
+        resp = resp
+            .with_header(ContentEncoding(vec![Encoding::Gzip]))
+            .with_body(Box::new(stream) as Box<Stream<Item = Chunk, Error = ::hyper::Error> + Send>);
+    } else {
+        let (offset, size) = match byte_range {
+            Some((start, end)) => (start, end - start + 1),
+            None => (0, len),
+        };
+
+        if let Some((start, end)) = byte_range {
+            resp = resp
+                .with_status(StatusCode::PartialContent)
+                .with_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: Some((start, end)),
+                    instance_length: Some(len),
+                }));
+        }
+
+        resp = resp.with_header(ContentLength(size));
+        if !is_head {
+            let stream = ChunkedReadFile::new(file, offset, size, pool).map_err(Error::into_hyper_error);
+            resp = resp.with_body(Box::new(stream) as Box<Stream<Item = Chunk, Error = ::hyper::Error> + Send>);
+        }
     }
 
     Ok(resp)
 }
 
-fn content_type(path: &Path) -> Option<ContentType> {
-    let ext = match path.extension().and_then(|o| o.to_str()) {
-        Some(ext) => ext,
-        None => return None,
+/// Picks the best precompressed sidecar (`<path>.br` / `<path>.gz`) for the
+/// client's `Accept-Encoding`, preferring a higher quality value and, on a
+/// tie, `br` over `gzip`. A sidecar only counts if it exists and is at least
+/// as new as the original file, so a stale sidecar never shadows an updated
+/// source file.
+fn best_sidecar(
+    canonical: &Path,
+    root: &Path,
+    accept_encoding: &Option<AcceptEncoding>,
+    original_modified: SystemTime,
+) -> Result<Option<(File, u64, Encoding)>, Error> {
+    let accept_encoding = match *accept_encoding {
+        Some(ref a) => a,
+        None => return Ok(None),
+    };
+    let real_root = root.canonicalize()?;
+
+    let mut candidates: Vec<&QualityItem<Encoding>> = accept_encoding
+        .iter()
+        .filter(|q| q.quality.0 > 0)
+        .filter(|q| q.item == Encoding::Brotli || q.item == Encoding::Gzip)
+        .collect();
+    candidates.sort_by(|a, b| {
+        b.quality.cmp(&a.quality).then_with(|| match (&a.item, &b.item) {
+            (&Encoding::Brotli, &Encoding::Gzip) => Ordering::Less,
+            (&Encoding::Gzip, &Encoding::Brotli) => Ordering::Greater,
+            _ => Ordering::Equal,
+        })
+    });
+
+    for q in candidates {
+        let suffix = match q.item {
+            Encoding::Brotli => ".br",
+            Encoding::Gzip => ".gz",
+            _ => continue,
+        };
+        let mut sidecar_name = canonical.as_os_str().to_owned();
+        sidecar_name.push(suffix);
+        let sidecar_path = PathBuf::from(sidecar_name);
+
+        let file = match File::open(&sidecar_path) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+
+        // Same confinement guard `read_file` applies to `canonical`: a
+        // sidecar that's secretly a symlink out of `root` must not be served.
+        let real_sidecar_path = sidecar_path.canonicalize()?;
+        if !real_sidecar_path.starts_with(&real_root) {
+            continue;
+        }
+
+        let metadata = file.metadata()?;
+        if metadata.modified()? >= original_modified {
+            return Ok(Some((file, metadata.len(), q.item.clone())));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Renders an HTML directory listing for `dir`, used as a fallback when a
+/// directory has no `index.html` and the server was started with
+/// `--autoindex`.
+fn render_autoindex(dir: &Path, root: &Path, is_head: bool) -> Result<Response, Error> {
+    let real_root = root.canonicalize()?;
+    let real_dir = dir.canonicalize()?;
+    if !real_dir.starts_with(&real_root) {
+        return Ok(Response::new().with_status(StatusCode::Forbidden));
+    }
+
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index</title></head>\n<body>\n<ul>\n");
+    for entry in entries {
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let metadata = entry.metadata()?;
+        let display_name = if metadata.is_dir() {
+            format!("{}/", name)
+        } else {
+            name
+        };
+        let modified = metadata.modified().map(HttpDate::from).ok();
+
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a> {} {}</li>\n",
+            percent_encode_path_segment(&display_name),
+            html_escape(&display_name),
+            metadata.len(),
+            modified.map(|d| d.to_string()).unwrap_or_default(),
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+
+    let body = html.into_bytes();
+    let mut resp = Response::new()
+        .with_header(ContentType::html())
+        .with_header(ContentLength(body.len() as u64));
+    if !is_head {
+        resp = resp.with_body(body);
+    }
+    Ok(resp)
+}
+
+/// Percent-encodes a single path segment for use in an href, leaving the
+/// small set of characters that are always safe in a path untouched.
+fn percent_encode_path_segment(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for b in segment.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(b as char);
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Escapes the handful of characters that are meaningful in HTML so an
+/// arbitrary file name can't break out of the `<li>` it's rendered in.
+fn html_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Checks a request's conditional headers against the current validators,
+/// per RFC 7232 §6: `If-None-Match` takes precedence over
+/// `If-Modified-Since` whenever both are present.
+fn is_not_modified(conditional: &Conditional, etag: &EntityTag, last_modified: &HttpDate) -> bool {
+    if let Some(ref if_none_match) = conditional.if_none_match {
+        return match *if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(ref tags) => tags.iter().any(|t| t.weak_eq(etag)),
+        };
+    }
+
+    if let Some(IfModifiedSince(ref since)) = conditional.if_modified_since {
+        return since >= last_modified;
+    }
+
+    false
+}
+
+/// Resolves a `Range` header against the file's total length, returning
+/// `Ok(Some((start, end)))` for a satisfiable inclusive byte range,
+/// `Ok(None)` when no range was requested (serve the whole file), or
+/// `Err(())` when the range cannot be satisfied and a `416` is warranted.
+///
+/// Only the first range in the header is honored; we don't support
+/// multipart/byteranges responses.
+fn satisfiable_range(range: Option<Range>, total: u64) -> Result<Option<(u64, u64)>, ()> {
+    let spec = match range {
+        Some(Range::Bytes(ref specs)) => match specs.first() {
+            Some(spec) => spec.clone(),
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
     };
-    match ext {
-        "jpg" | "jpeg" => Some(ContentType::jpeg()),
-        "png" => Some(ContentType::png()),
-        "txt" | "md" => Some(ContentType::plaintext()),
-        "html" => Some(ContentType::html()),
-        "xml" => Some(ContentType::xml()),
-        "json" => Some(ContentType::json()),
-        "gif" => "image/gif".parse().ok().map(ContentType),
-        "css" => "text/css".parse().ok().map(ContentType),
-        _ => ext.parse().ok().map(ContentType),
+
+    match spec.to_satisfiable_range(total) {
+        Some(r) => Ok(Some(r)),
+        None => Err(()),
     }
 }
 
+/// A `Stream` of `Chunk`s that reads a file incrementally on a `CpuPool`
+/// rather than buffering it into memory up front, so serving large files
+/// keeps a bounded memory footprint and doesn't monopolize a pool thread.
+struct ChunkedReadFile {
+    file: Option<File>,
+    offset: u64,
+    remaining: u64,
+    pool: CpuPool,
+    in_flight: Option<CpuFuture<(File, Vec<u8>), Error>>,
+}
+
+impl ChunkedReadFile {
+    fn new(file: File, offset: u64, size: u64, pool: CpuPool) -> Self {
+        ChunkedReadFile {
+            file: Some(file),
+            offset: offset,
+            remaining: size,
+            pool: pool,
+            in_flight: None,
+        }
+    }
+}
+
+impl Stream for ChunkedReadFile {
+    type Item = Chunk;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, Error> {
+        if self.remaining == 0 {
+            return Ok(Async::Ready(None));
+        }
+
+        if self.in_flight.is_none() {
+            let file = self.file
+                .take()
+                .expect("ChunkedReadFile polled after completion");
+            let offset = self.offset;
+            let want = cmp::min(self.remaining, CHUNK_SIZE);
+            self.in_flight = Some(self.pool.spawn_fn(move || read_chunk(file, offset, want)));
+        }
+
+        let (file, buf) = match self.in_flight.as_mut().unwrap().poll()? {
+            Async::Ready(v) => v,
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+        self.in_flight = None;
+        self.file = Some(file);
+
+        if buf.is_empty() {
+            self.remaining = 0;
+            return Ok(Async::Ready(None));
+        }
+
+        self.offset += buf.len() as u64;
+        self.remaining -= buf.len() as u64;
+        Ok(Async::Ready(Some(Chunk::from(buf))))
+    }
+}
+
+fn read_chunk(mut file: File, offset: u64, want: u64) -> Result<(File, Vec<u8>), Error> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0; want as usize];
+    let n = file.read(&mut buf)?;
+    buf.truncate(n);
+    Ok((file, buf))
+}
+
+/// A `Stream` of `Chunk`s that gzips a file on the pool as it's read,
+/// rather than buffering the whole file (and its compressed copy) into
+/// memory before sending anything. Since the compressed size isn't known
+/// ahead of time, the response built over this stream has no
+/// `Content-Length`.
+struct GzipReadFile {
+    encoder: Option<GzEncoder<BufReader<File>>>,
+    pool: CpuPool,
+    in_flight: Option<CpuFuture<(GzEncoder<BufReader<File>>, Vec<u8>), Error>>,
+    done: bool,
+}
+
+impl GzipReadFile {
+    fn new(file: File, pool: CpuPool) -> Self {
+        let encoder = GzEncoder::new(BufReader::new(file), Compression::Fast);
+        GzipReadFile {
+            encoder: Some(encoder),
+            pool: pool,
+            in_flight: None,
+            done: false,
+        }
+    }
+}
+
+impl Stream for GzipReadFile {
+    type Item = Chunk;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+
+        if self.in_flight.is_none() {
+            let encoder = self.encoder
+                .take()
+                .expect("GzipReadFile polled after completion");
+            self.in_flight = Some(self.pool.spawn_fn(move || read_gzip_chunk(encoder)));
+        }
+
+        let (encoder, buf) = match self.in_flight.as_mut().unwrap().poll()? {
+            Async::Ready(v) => v,
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+        self.in_flight = None;
+
+        if buf.is_empty() {
+            self.done = true;
+            return Ok(Async::Ready(None));
+        }
+
+        self.encoder = Some(encoder);
+        Ok(Async::Ready(Some(Chunk::from(buf))))
+    }
+}
+
+fn read_gzip_chunk(
+    mut encoder: GzEncoder<BufReader<File>>,
+) -> Result<(GzEncoder<BufReader<File>>, Vec<u8>), Error> {
+    let mut buf = vec![0; CHUNK_SIZE as usize];
+    let n = encoder.read(&mut buf)?;
+    buf.truncate(n);
+    Ok((encoder, buf))
+}
+
+fn content_type(path: &Path) -> Option<ContentType> {
+    // mime_guess gets most extensions right from the system MIME database,
+    // but is ambiguous for a couple of extensions browsers are picky about.
+    let mime = match path.extension().and_then(|o| o.to_str()) {
+        Some("js") | Some("mjs") => mime::TEXT_JAVASCRIPT,
+        _ => mime_guess::from_path(path).first_or_octet_stream(),
+    };
+
+    let mime = if is_text(&mime) {
+        format!("{}; charset=utf-8", mime).parse().unwrap_or(mime)
+    } else {
+        mime
+    };
+
+    Some(ContentType(mime))
+}
+
+fn is_text(mime: &Mime) -> bool {
+    mime.type_() == mime::TEXT || *mime == mime::APPLICATION_JAVASCRIPT
+        || *mime == mime::APPLICATION_JSON
+}
+
 struct RequestLogger(Request, ResponseFuture, u64);
 
 impl RequestLogger {
@@ -138,7 +631,8 @@ impl Future for RequestLogger {
 
 enum ResponseFuture {
     Found(CpuFuture<Response, Error>),
-    NotAllowed,
+    Status(StatusCode),
+    Redirect(String),
 }
 
 impl Future for ResponseFuture {
@@ -148,8 +642,14 @@ impl Future for ResponseFuture {
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let inner = match *self {
             ResponseFuture::Found(ref mut i) => i,
-            ResponseFuture::NotAllowed => {
-                let res = Response::new().with_status(StatusCode::MethodNotAllowed);
+            ResponseFuture::Status(status) => {
+                let res = Response::new().with_status(status);
+                return Ok(Async::Ready(res));
+            }
+            ResponseFuture::Redirect(ref location) => {
+                let res = Response::new()
+                    .with_status(StatusCode::PermanentRedirect)
+                    .with_header(Location(location.clone()));
                 return Ok(Async::Ready(res));
             }
         };
@@ -178,47 +678,80 @@ impl Service for StaticServer {
 
     fn call(&self, req: Request) -> Self::Future {
         let req_start = time::precise_time_ns();
-        if *req.method() != Method::Get {
-            return RequestLogger(req, ResponseFuture::NotAllowed, req_start);
-        }
+        let is_head = match *req.method() {
+            Method::Get => false,
+            Method::Head => true,
+            _ => {
+                return RequestLogger(req, ResponseFuture::Status(StatusCode::MethodNotAllowed), req_start);
+            }
+        };
+        let raw_path = req.path().to_owned();
         let path = {
-            // Strip the leading '/' since PathBuf will overwrite
-            PathBuf::from(&req.path()[1..])
+            // Strip the leading '/' since PathBuf will overwrite, then
+            // percent-decode so encoded spaces/unicode resolve to the
+            // right file instead of a literal `%20`-named one.
+            let decoded = match percent_decode(&raw_path[1..]) {
+                Ok(decoded) => decoded,
+                Err(_) => {
+                    return RequestLogger(req, ResponseFuture::Status(StatusCode::BadRequest), req_start);
+                }
+            };
+            PathBuf::from(decoded)
+        };
+        let accept_encoding = req.headers().get::<AcceptEncoding>().cloned();
+        let range = req.headers().get::<Range>().cloned();
+        let conditional = Conditional {
+            if_none_match: req.headers().get::<IfNoneMatch>().cloned(),
+            if_modified_since: req.headers().get::<IfModifiedSince>().cloned(),
         };
-        let gzip = req.headers()
-            .get::<AcceptEncoding>()
-            .map(|es| es.iter().any(|q| q.item == Encoding::Gzip))
-            .unwrap_or(false);
 
-        RequestLogger(req, self.spawn_read(&path, gzip), req_start)
+        RequestLogger(
+            req,
+            self.spawn_read(&path, &raw_path, accept_encoding, range, conditional, is_head),
+            req_start,
+        )
     }
 }
 
+/// The validators a request may carry for conditional `GET`s.
+struct Conditional {
+    if_none_match: Option<IfNoneMatch>,
+    if_modified_since: Option<IfModifiedSince>,
+}
+
 struct Params {
     root: PathBuf,
     port: u16,
+    autoindex: bool,
 }
 
 impl Params {
     fn parse() -> Self {
-        let mut args = env::args();
-        args.next();
-
-        let root = args.next()
-            .map(PathBuf::from)
-            .unwrap_or_else(|| "./public".into());
-
-        let port = args.next()
-            .and_then(|p| p.parse::<u16>().ok())
-            .unwrap_or(8080);
+        let mut root = None;
+        let mut port = None;
+        let mut autoindex = false;
+
+        for arg in env::args().skip(1) {
+            if arg == "--autoindex" {
+                autoindex = true;
+            } else if root.is_none() {
+                root = Some(PathBuf::from(arg));
+            } else if port.is_none() {
+                port = arg.parse::<u16>().ok();
+            }
+        }
 
-        Params { root, port }
+        Params {
+            root: root.unwrap_or_else(|| "./public".into()),
+            port: port.unwrap_or(8080),
+            autoindex: autoindex,
+        }
     }
 }
 
 fn main() {
     pretty_env_logger::init().unwrap();
-    let Params { root, port } = Params::parse();
+    let Params { root, port, autoindex } = Params::parse();
     let pool = PoolBuilder::new()
         .pool_size(4)
         .name_prefix("fs-thread")
@@ -230,6 +763,7 @@ fn main() {
     let service = StaticServer {
         root: root,
         pool: pool,
+        autoindex: autoindex,
     };
     let server = Http::new()
         .bind(&addr, move || Ok(service.clone()))
@@ -237,3 +771,132 @@ fn main() {
 
     server.run().unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::ByteRangeSpec;
+    use std::time::Duration;
+
+    fn conditional(
+        if_none_match: Option<IfNoneMatch>,
+        if_modified_since: Option<IfModifiedSince>,
+    ) -> Conditional {
+        Conditional {
+            if_none_match: if_none_match,
+            if_modified_since: if_modified_since,
+        }
+    }
+
+    #[test]
+    fn is_not_modified_with_no_validators_is_false() {
+        let etag = EntityTag::weak("1-1000".to_owned());
+        let last_modified = HttpDate::from(UNIX_EPOCH + Duration::from_secs(1000));
+        assert!(!is_not_modified(&conditional(None, None), &etag, &last_modified));
+    }
+
+    #[test]
+    fn is_not_modified_matches_if_none_match() {
+        let etag = EntityTag::weak("1-1000".to_owned());
+        let last_modified = HttpDate::from(UNIX_EPOCH + Duration::from_secs(1000));
+        let cond = conditional(Some(IfNoneMatch::Items(vec![etag.clone()])), None);
+        assert!(is_not_modified(&cond, &etag, &last_modified));
+    }
+
+    #[test]
+    fn is_not_modified_mismatched_if_none_match_is_false() {
+        let etag = EntityTag::weak("1-1000".to_owned());
+        let other = EntityTag::weak("2-2000".to_owned());
+        let last_modified = HttpDate::from(UNIX_EPOCH + Duration::from_secs(1000));
+        let cond = conditional(Some(IfNoneMatch::Items(vec![other])), None);
+        assert!(!is_not_modified(&cond, &etag, &last_modified));
+    }
+
+    #[test]
+    fn is_not_modified_matches_if_modified_since() {
+        let etag = EntityTag::weak("1-1000".to_owned());
+        let last_modified = HttpDate::from(UNIX_EPOCH + Duration::from_secs(1000));
+        let cond = conditional(None, Some(IfModifiedSince(last_modified.clone())));
+        assert!(is_not_modified(&cond, &etag, &last_modified));
+    }
+
+    #[test]
+    fn is_not_modified_stale_if_modified_since_is_false() {
+        let etag = EntityTag::weak("1-1000".to_owned());
+        let last_modified = HttpDate::from(UNIX_EPOCH + Duration::from_secs(1000));
+        let since = HttpDate::from(UNIX_EPOCH + Duration::from_secs(500));
+        let cond = conditional(None, Some(IfModifiedSince(since)));
+        assert!(!is_not_modified(&cond, &etag, &last_modified));
+    }
+
+    #[test]
+    fn is_not_modified_if_none_match_takes_precedence() {
+        // RFC 7232 section 6: If-None-Match wins even if If-Modified-Since
+        // would otherwise indicate the cached copy is fresh.
+        let etag = EntityTag::weak("1-1000".to_owned());
+        let other = EntityTag::weak("2-2000".to_owned());
+        let last_modified = HttpDate::from(UNIX_EPOCH + Duration::from_secs(1000));
+        let cond = conditional(
+            Some(IfNoneMatch::Items(vec![other])),
+            Some(IfModifiedSince(last_modified.clone())),
+        );
+        assert!(!is_not_modified(&cond, &etag, &last_modified));
+    }
+
+    #[test]
+    fn satisfiable_range_with_no_header_serves_whole_file() {
+        assert_eq!(satisfiable_range(None, 100), Ok(None));
+    }
+
+    #[test]
+    fn satisfiable_range_from_to() {
+        let range = Range::Bytes(vec![ByteRangeSpec::FromTo(0, 9)]);
+        assert_eq!(satisfiable_range(Some(range), 100), Ok(Some((0, 9))));
+    }
+
+    #[test]
+    fn satisfiable_range_all_from() {
+        let range = Range::Bytes(vec![ByteRangeSpec::AllFrom(90)]);
+        assert_eq!(satisfiable_range(Some(range), 100), Ok(Some((90, 99))));
+    }
+
+    #[test]
+    fn satisfiable_range_last_n_bytes() {
+        let range = Range::Bytes(vec![ByteRangeSpec::Last(10)]);
+        assert_eq!(satisfiable_range(Some(range), 100), Ok(Some((90, 99))));
+    }
+
+    #[test]
+    fn satisfiable_range_rejects_start_past_end_of_file() {
+        let range = Range::Bytes(vec![ByteRangeSpec::AllFrom(100)]);
+        assert_eq!(satisfiable_range(Some(range), 100), Err(()));
+    }
+
+    #[test]
+    fn percent_decode_passes_through_plain_paths() {
+        assert_eq!(percent_decode("foo/bar.txt").unwrap(), "foo/bar.txt");
+    }
+
+    #[test]
+    fn percent_decode_decodes_escapes() {
+        assert_eq!(percent_decode("my%20file.txt").unwrap(), "my file.txt");
+    }
+
+    #[test]
+    fn percent_decode_rejects_invalid_hex() {
+        assert!(percent_decode("%zz").is_err());
+    }
+
+    #[test]
+    fn percent_decode_rejects_truncated_escape() {
+        assert!(percent_decode("abc%2").is_err());
+    }
+
+    #[test]
+    fn percent_decode_does_not_panic_on_non_char_boundary_input() {
+        // A stray '%' immediately followed by a multi-byte UTF-8 character
+        // used to be sliced by raw byte offset and panic; it must now just
+        // fail to parse as a hex escape.
+        assert!(percent_decode("%\u{20ac}abc").is_err());
+    }
+}